@@ -1,16 +1,23 @@
 mod package;
 mod protocol;
 mod transport;
+mod transport_bluest;
 mod transport_btleplug;
 
+use anyhow::Context;
 use clap::{Parser, Subcommand};
+use transport::DfuTransportManager;
 
 /// Update firmware on nRF BLE DFU targets
 #[derive(clap::Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// BLE DFU target name or address
-    target: String,
+    /// BLE DFU target name or address (not required by `scan`)
+    target: Option<String>,
+
+    /// Use the `bluest` backend (targets by peripheral identifier or name; needed to target by identifier on macOS)
+    #[arg(long)]
+    bluest: bool,
 
     #[command(subcommand)]
     command: Commands,
@@ -18,6 +25,12 @@ struct Args {
 
 #[derive(Subcommand)]
 enum Commands {
+    /// List DFU-capable peripherals advertising nearby
+    Scan {
+        /// How long to scan for, in seconds
+        #[arg(long, default_value_t = 5)]
+        duration: u64,
+    },
     /// Start DFU mode using Buttonless DFU Service
     Trigger {},
     /// Update application
@@ -45,9 +58,24 @@ enum Commands {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    let transport_manager = transport_btleplug::DfuTransportManagerBtleplug::new().await?;
+    if let Commands::Scan { duration } = &args.command {
+        let manager = transport_btleplug::DfuTransportManagerBtleplug::new().await?;
+        return manager.scan(std::time::Duration::from_secs(*duration)).await;
+    }
+    // The `bluest` backend targets by platform identifier (the only option
+    // CoreBluetooth exposes on macOS) and is used only with `--bluest`;
+    // `btleplug` is the default on every platform.
+    if args.bluest {
+        dispatch(transport_bluest::DfuTransportManagerBluest::new().await?, args).await
+    } else {
+        dispatch(transport_btleplug::DfuTransportManagerBtleplug::new().await?, args).await
+    }
+}
+
+async fn dispatch<T: DfuTransportManager>(transport_manager: T, args: Args) -> anyhow::Result<()> {
+    let target = args.target.as_deref().context("a target name or address is required")?;
     if let Commands::Trigger {} = &args.command {
-        protocol::dfu_trigger(transport_manager, &args.target).await
+        protocol::dfu_trigger(transport_manager, target).await
     } else {
         let (init_pkt, fw_pkt) = match &args.command {
             Commands::App { pkg } => package::extract_application(pkg)?,
@@ -56,6 +84,6 @@ async fn main() -> anyhow::Result<()> {
             Commands::Sdbl { pkg } => package::extract_softdevice_bootloader(pkg)?,
             _ => unreachable!(),
         };
-        protocol::dfu_run(transport_manager, &args.target, &init_pkt, &fw_pkt).await
+        protocol::dfu_run(transport_manager, target, &init_pkt, &fw_pkt).await
     }
 }
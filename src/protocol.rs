@@ -97,16 +97,183 @@ enum ExtError {
     InsufficientSpace = 0x0D,
 }
 
+/// Version of a firmware image stored on the target, as reported by `FirmwareVersion`.
+#[derive(Debug)]
+struct FwVersion {
+    /// Image type (0 = SoftDevice, 1 = application, 2 = bootloader)
+    img_type: u8,
+    version: u32,
+    addr: u32,
+    len: u32,
+}
+
+/// Target hardware identification, as reported by `HardwareVersion`.
+#[derive(Debug)]
+struct HwVersion {
+    part: u32,
+    variant: u32,
+    rom_size: u32,
+    ram_size: u32,
+}
+
 fn crc32(buf: &[u8], init: u32) -> u32 {
     let mut h = crc32fast::Hasher::new_with_initial(init);
     h.update(buf);
     h.finalize()
 }
 
+/// Compatibility requirements carved out of the DFU init packet.
+///
+/// The init packet is a `dfu-cc` protobuf; only the `sd_req` list (the set of
+/// SoftDevice firmware ids the image will accept) is needed to fail early on an
+/// incompatible target. `SD_REQ_ANY` (0xFFFE) or an empty list means "any".
+struct ImageRequirements {
+    sd_req: Vec<u32>,
+}
+
+/// SoftDevice requirement wildcard accepting any installed SoftDevice.
+const SD_REQ_ANY: u32 = 0xFFFE;
+
+/// A single protobuf field value limited to the wire types the init packet uses.
+enum PbValue {
+    Varint(u64),
+    Bytes(Vec<u8>),
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *buf.get(*pos).context("init packet: truncated varint")?;
+        *pos += 1;
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        anyhow::ensure!(shift < 64, "init packet: varint too long");
+    }
+    Ok(result)
+}
+
+/// Decode one protobuf message into its `(field_number, value)` pairs.
+fn pb_fields(buf: &[u8]) -> Result<Vec<(u64, PbValue)>> {
+    let mut pos = 0;
+    let mut fields = Vec::new();
+    while pos < buf.len() {
+        let key = read_varint(buf, &mut pos)?;
+        let (field, wire) = (key >> 3, key & 0x7);
+        match wire {
+            0 => fields.push((field, PbValue::Varint(read_varint(buf, &mut pos)?))),
+            2 => {
+                let len = read_varint(buf, &mut pos)? as usize;
+                let end = pos.checked_add(len).context("init packet: length overflow")?;
+                anyhow::ensure!(end <= buf.len(), "init packet: truncated field");
+                fields.push((field, PbValue::Bytes(buf[pos..end].to_vec())));
+                pos = end;
+            }
+            1 => pos += 8,
+            5 => pos += 4,
+            _ => anyhow::bail!("init packet: unsupported wire type {}", wire),
+        }
+    }
+    Ok(fields)
+}
+
+fn field_bytes(fields: &[(u64, PbValue)], num: u64) -> Option<&[u8]> {
+    fields.iter().find_map(|(n, v)| match v {
+        PbValue::Bytes(b) if *n == num => Some(b.as_slice()),
+        _ => None,
+    })
+}
+
+/// Parse the `sd_req` list out of the init packet.
+///
+/// `Packet { command | signed_command } -> Command -> InitCommand.sd_req`, where
+/// `sd_req` (field 3) is a repeated `uint32`, sent either unpacked or packed.
+fn parse_init_packet(init: &[u8]) -> Result<ImageRequirements> {
+    let packet = pb_fields(init)?;
+    let command = match field_bytes(&packet, 1) {
+        Some(cmd) => cmd.to_vec(),
+        None => {
+            let signed = pb_fields(field_bytes(&packet, 2).context("init packet: no command")?)?;
+            field_bytes(&signed, 1).context("init packet: missing signed command")?.to_vec()
+        }
+    };
+    let init_cmd = pb_fields(field_bytes(&pb_fields(&command)?, 2).context("init packet: missing init command")?)?;
+
+    let mut sd_req = Vec::new();
+    for (num, value) in &init_cmd {
+        if *num != 3 {
+            continue;
+        }
+        match value {
+            PbValue::Varint(v) => sd_req.push(*v as u32),
+            PbValue::Bytes(packed) => {
+                let mut pos = 0;
+                while pos < packed.len() {
+                    sd_req.push(read_varint(packed, &mut pos)? as u32);
+                }
+            }
+        }
+    }
+    Ok(ImageRequirements { sd_req })
+}
+
+/// Outcome of comparing the image's `sd_req` against the target's SoftDevice.
+enum SdCompat {
+    /// Compatible, or no actionable constraint — proceed.
+    Ok,
+    /// Uncertain mismatch; warn but let the bootloader make the final call.
+    Warn(String),
+    /// Unambiguously incompatible; fail before uploading.
+    Incompatible(String),
+}
+
+/// Compare the image's `sd_req` list against the SoftDevice on the target.
+///
+/// An empty list or the `SD_REQ_ANY` wildcard impose no constraint; the
+/// no-SoftDevice marker (`0`) accepts a target without a SoftDevice. A present SoftDevice whose id is absent from a concrete
+/// `sd_req` only *warns*: the `FirmwareVersion` opcode's `version` field has not
+/// been validated against the init packet's FWID namespace on real hardware, so
+/// blocking on it risks rejecting valid images. A target with no SoftDevice and
+/// an image that requires one (no `0` in `sd_req`) is the one case we can reject
+/// with confidence, since it does not rely on the `version` field at all.
+fn check_sd_requirement(sd_req: &[u32], present: Option<u32>) -> SdCompat {
+    if sd_req.is_empty() || sd_req.contains(&SD_REQ_ANY) {
+        return SdCompat::Ok;
+    }
+    match present {
+        Some(id) if sd_req.contains(&id) => SdCompat::Ok,
+        Some(id) => SdCompat::Warn(format!(
+            "target SoftDevice id {} not listed in image sd_req {:?}",
+            id, sd_req
+        )),
+        None if sd_req.contains(&0) => SdCompat::Ok,
+        None => SdCompat::Incompatible(format!(
+            "image requires a SoftDevice (sd_req {:?}) but target reports none",
+            sd_req
+        )),
+    }
+}
+
+/// Data packet size used when the target does not report an MTU.
+///
+/// The default nRF DFU ATT MTU is 247, leaving 244 bytes for the payload.
+const DEFAULT_CHUNK_SIZE: usize = 244;
+
+/// ATT opcode + handle overhead deducted from the negotiated MTU.
+const ATT_WRITE_OVERHEAD: usize = 3;
+
+/// Packet Receipt Notification interval: the target reports a CRC every N packets.
+const PRN_INTERVAL: u32 = 8;
+
 // More requests are available when `NRF_DFU_PROTOCOL_REDUCED` is not defined
 // in `nRF5_SDK_17.1.0_ddde560/components/libraries/bootloader/dfu/nrf_dfu_req_handler.c`
 struct DfuTarget<T: DfuTransport> {
     transport: T,
+    /// Data-characteristic write size, negotiated via [`get_mtu`](Self::get_mtu).
+    chunk_size: usize,
 }
 
 impl<T: DfuTransport> DfuTarget<T> {
@@ -129,10 +296,25 @@ impl<T: DfuTransport> DfuTarget<T> {
     }
 
     async fn write_data(&self, bytes: &[u8]) -> Result<()> {
-        let write = self.transport.write(dfu_uuids::DATA_PT, bytes);
+        let write = self.transport.write(dfu_uuids::DATA_PT, bytes, self.chunk_size);
         timeout(Duration::from_millis(500), write).await?
     }
 
+    /// Query the negotiated ATT MTU and derive the usable data packet size.
+    ///
+    /// Falls back to [`DEFAULT_CHUNK_SIZE`] when the bootloader predates the
+    /// `MtuGet` opcode and answers with `OpCodeNotSupported`.
+    async fn get_mtu(&self) -> Result<usize> {
+        let opcode = OpCode::MtuGet;
+        let response = self.request_ctrl(&[opcode as u8]).await?;
+        if response.get(2) == Some(&(ResponseCode::OpCodeNotSupported as u8)) {
+            return Ok(DEFAULT_CHUNK_SIZE);
+        }
+        Self::verify_response(opcode, &response)?;
+        let mtu = u16::from_le_bytes(response[3..5].try_into()?) as usize;
+        Ok(mtu.saturating_sub(ATT_WRITE_OVERHEAD))
+    }
+
     async fn request_ctrl(&self, bytes: &[u8]) -> Result<Vec<u8>> {
         for _retry in 0..3 {
             let request = self.transport.request(dfu_uuids::CTRL_PT, bytes);
@@ -143,6 +325,14 @@ impl<T: DfuTransport> DfuTarget<T> {
         Err(anyhow!("No response after multiple tries"))
     }
 
+    /// Discard any buffered or late control-point notifications.
+    ///
+    /// Used on the PRN error path so a stale CRC notification from before the
+    /// cursor rewind is not mistaken for the response to the next request.
+    async fn drain_ctrl(&self) {
+        while let Ok(Ok(_)) = timeout(Duration::from_millis(100), self.transport.notify(dfu_uuids::CTRL_PT)).await {}
+    }
+
     async fn set_prn(&self, value: u32) -> Result<()> {
         let opcode = OpCode::ReceiptNotifSet;
         let mut payload: Vec<u8> = vec![opcode as u8];
@@ -194,14 +384,104 @@ impl<T: DfuTransport> DfuTarget<T> {
         anyhow::ensure!(expected_crc == crc, "CRC mismatch");
         Ok(())
     }
+
+    /// Query the version of a stored firmware image by type index
+    /// (0 = SoftDevice, 1 = application, 2 = bootloader).
+    ///
+    /// Returns `None` when the bootloader does not implement `FirmwareVersion`,
+    /// which is the case on reduced-protocol builds.
+    async fn get_firmware_version(&self, image: u8) -> Result<Option<FwVersion>> {
+        let opcode = OpCode::FirmwareVersion;
+        let response = self.request_ctrl(&[opcode as u8, image]).await?;
+        if response.get(2) == Some(&(ResponseCode::OpCodeNotSupported as u8)) {
+            return Ok(None);
+        }
+        Self::verify_response(opcode, &response)?;
+        Ok(Some(FwVersion {
+            img_type: response[3],
+            version: u32::from_le_bytes(response[4..8].try_into()?),
+            addr: u32::from_le_bytes(response[8..12].try_into()?),
+            len: u32::from_le_bytes(response[12..16].try_into()?),
+        }))
+    }
+
+    /// Query the target's hardware identification.
+    ///
+    /// Returns `None` when the bootloader does not implement `HardwareVersion`.
+    async fn get_hardware_version(&self) -> Result<Option<HwVersion>> {
+        let opcode = OpCode::HardwareVersion;
+        let response = self.request_ctrl(&[opcode as u8]).await?;
+        if response.get(2) == Some(&(ResponseCode::OpCodeNotSupported as u8)) {
+            return Ok(None);
+        }
+        Self::verify_response(opcode, &response)?;
+        Ok(Some(HwVersion {
+            part: u32::from_le_bytes(response[3..7].try_into()?),
+            variant: u32::from_le_bytes(response[7..11].try_into()?),
+            rom_size: u32::from_le_bytes(response[11..15].try_into()?),
+            ram_size: u32::from_le_bytes(response[15..19].try_into()?),
+        }))
+    }
+
+    /// Validate a PRN CRC notification against the locally accumulated state.
+    fn check_prn_crc(ntf: &[u8], offset: usize, checksum: u32) -> Result<()> {
+        Self::verify_response(OpCode::CrcGet, ntf)?;
+        let rep_offset = u32::from_le_bytes(ntf[3..7].try_into()?) as usize;
+        let rep_crc = u32::from_le_bytes(ntf[7..11].try_into()?);
+        anyhow::ensure!(rep_offset == offset, "PRN offset mismatch");
+        anyhow::ensure!(rep_crc == checksum, "PRN CRC mismatch");
+        Ok(())
+    }
+
+    /// Stream a single data object, relying on Packet Receipt Notifications for
+    /// flow control instead of a round-trip after every packet.
+    ///
+    /// Packets are written back-to-back; the target emits a CRC notification
+    /// every `PRN_INTERVAL` of them, which is checked against the running CRC as
+    /// it arrives. The object's trailing packets are confirmed with an explicit
+    /// `get_crc`. A mismatch aborts the object so the caller can recreate it and
+    /// retransmit from the last confirmed offset.
+    ///
+    /// `pending` is the transfer-wide packet counter: the target's receipt
+    /// counter is reset only by `set_prn`, not by `ObjectCreate`/`Execute`, so
+    /// it must carry across object boundaries or the local `== PRN_INTERVAL`
+    /// check drifts away from where the device actually fires its notification.
+    async fn stream_data_object(
+        &self,
+        chunk: &[u8],
+        base_offset: usize,
+        base_checksum: u32,
+        pending: &mut u32,
+    ) -> Result<(usize, u32)> {
+        let mut offset = base_offset;
+        let mut checksum = base_checksum;
+        for packet in chunk.chunks(self.chunk_size) {
+            self.write_data(packet).await?;
+            offset += packet.len();
+            checksum = crc32(packet, checksum);
+            *pending += 1;
+            if *pending == PRN_INTERVAL {
+                *pending = 0;
+                let notify = self.transport.notify(dfu_uuids::CTRL_PT);
+                let ntf = timeout(Duration::from_millis(500), notify).await??;
+                Self::check_prn_crc(&ntf, offset, checksum)?;
+            }
+        }
+        // Confirm any trailing packets that did not complete a receipt interval.
+        if *pending != 0 {
+            self.verify_crc(offset, checksum).await?;
+        }
+        Ok((offset, checksum))
+    }
 }
 
 /// Run DFU procedure as specified in
 /// [DFU Protocol](https://infocenter.nordicsemi.com/topic/sdk_nrf5_v17.1.0/lib_dfu_transport_ble.html)
 pub async fn dfu_run<T: DfuTransportManager>(manager: T, name: &str, init_pkt: &[u8], fw_pkt: &[u8]) -> Result<()> {
     let transport = manager.connect(name).await?;
-    let target = DfuTarget { transport };
+    let mut target = DfuTarget { transport, chunk_size: DEFAULT_CHUNK_SIZE };
     target.transport.subscribe(dfu_uuids::CTRL_PT).await?;
+    target.chunk_size = target.get_mtu().await?;
 
     let pb = ProgressBar::new(fw_pkt.len() as u64);
     pb.set_style(
@@ -212,37 +492,112 @@ pub async fn dfu_run<T: DfuTransportManager>(manager: T, name: &str, init_pkt: &
 
     pb.set_message("Uploading...");
 
-    // Disable packet receipt notifications
+    // Disable packet receipt notifications for the small, latency-insensitive
+    // command object so it can be verified with a single round-trip.
     target.set_prn(0).await?;
 
+    // Pre-flight: report the target's hardware (informational only) and
+    // cross-check the image's SoftDevice requirement so a clearly incompatible
+    // SoftDevice is caught here rather than after the init packet trips an
+    // SdVersionFailure mid-flash.
+    match target.get_hardware_version().await? {
+        Some(hw) => pb.println(format!(
+            "Hardware: part {:#x}, variant {:#x}, rom {}KiB, ram {}KiB",
+            hw.part,
+            hw.variant,
+            hw.rom_size / 1024,
+            hw.ram_size / 1024,
+        )),
+        None => pb.println("Hardware version query not supported by bootloader".to_string()),
+    }
+    let requirements = parse_init_packet(init_pkt).context("Failed to parse DFU init packet")?;
+    let compat = match target.get_firmware_version(0).await? {
+        Some(sd) if sd.len != 0 => {
+            pb.println(format!(
+                "SoftDevice: type {}, version {}, {} bytes at {:#x}",
+                sd.img_type, sd.version, sd.len, sd.addr
+            ));
+            check_sd_requirement(&requirements.sd_req, Some(sd.version))
+        }
+        Some(_) => {
+            pb.println("No SoftDevice present on target".to_string());
+            check_sd_requirement(&requirements.sd_req, None)
+        }
+        // Can't cross-check; the bootloader still enforces the init packet.
+        None => {
+            pb.println("Firmware version query not supported by bootloader".to_string());
+            SdCompat::Ok
+        }
+    };
+    match compat {
+        SdCompat::Ok => {}
+        SdCompat::Warn(msg) => pb.println(format!("Warning: {}; continuing", msg)),
+        SdCompat::Incompatible(msg) => anyhow::bail!(msg),
+    }
+
     target.create_object(Object::Command, init_pkt.len()).await?;
     target.write_data(init_pkt).await?;
     target.verify_crc(init_pkt.len(), crc32(init_pkt, 0)).await?;
     target.execute().await?;
 
-    let (max_size, offset, checksum) = target.select_object(Object::Data).await?;
-    if offset != 0 || checksum != 0 {
-        anyhow::bail!("DFU resumption is not supported");
-    }
+    let (max_size, dev_offset, dev_checksum) = target.select_object(Object::Data).await?;
     let mut checksum: u32 = 0;
     let mut offset: usize = 0;
+    if dev_offset != 0 {
+        // The target already holds part of this image from an interrupted run.
+        // Resume only if the accepted prefix matches byte-for-byte, otherwise the
+        // safe course is to discard it and upload from scratch.
+        if dev_offset <= fw_pkt.len() && crc32(&fw_pkt[..dev_offset], 0) == dev_checksum {
+            pb.println(format!("Resuming upload from offset {}", dev_offset));
+            offset = dev_offset;
+            checksum = dev_checksum;
+            // An offset that is not object-aligned means the current object was
+            // only partially written; finish its remaining bytes before moving on
+            // instead of re-creating it (which would reject the overlap).
+            if offset % max_size != 0 {
+                let obj_end = std::cmp::min(fw_pkt.len(), offset - (offset % max_size) + max_size);
+                let rest = &fw_pkt[offset..obj_end];
+                target.write_data(rest).await?;
+                checksum = crc32(rest, checksum);
+                offset = obj_end;
+                target.verify_crc(offset, checksum).await?;
+                target.execute().await?;
+                pb.set_position(offset as u64);
+            }
+        } else {
+            pb.println("Stored image does not match, restarting from scratch".to_string());
+        }
+    }
+    // Stream the firmware with receipt notifications so writes overlap with
+    // acknowledgements instead of serializing on BLE round-trip latency.
+    target.set_prn(PRN_INTERVAL).await?;
+    // Transfer-wide receipt counter, shared across all data objects.
+    let mut pending: u32 = 0;
     while offset < fw_pkt.len() {
         let end = std::cmp::min(fw_pkt.len(), offset + max_size);
         let chunk = &fw_pkt[offset..end];
         target.create_object(Object::Data, chunk.len()).await?;
-        target.write_data(chunk).await?;
-        let new_checksum = crc32(chunk, checksum);
-        let new_offset = offset + chunk.len();
-        if target.verify_crc(new_offset, new_checksum).await.is_err() {
-            pb.println(format!("CRC error at offset {}, retrying...", offset));
-            // first chunk frequently fails on macOS, backoff seems to help
-            tokio::time::sleep(Duration::from_millis(500)).await;
-            continue;
+        match target.stream_data_object(chunk, offset, checksum, &mut pending).await {
+            Ok((new_offset, new_checksum)) => {
+                checksum = new_checksum;
+                offset = new_offset;
+                pb.set_position(offset as u64);
+                target.execute().await?;
+            }
+            Err(err) => {
+                // Rewind to the last confirmed offset; the object is recreated
+                // and retransmitted on the next iteration.
+                pb.println(format!("CRC error at offset {} ({}), retrying...", offset, err));
+                // first chunk frequently fails on macOS, backoff seems to help
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                // Resynchronize flow control: drain any stale CRC notification,
+                // then reset both the device's global receipt counter (via
+                // set_prn) and our local one so the rewound cursor lines up.
+                target.drain_ctrl().await;
+                target.set_prn(PRN_INTERVAL).await?;
+                pending = 0;
+            }
         }
-        checksum = new_checksum;
-        offset = new_offset;
-        pb.set_position(offset as u64);
-        target.execute().await?;
     }
     pb.finish_with_message("Done");
 
@@ -263,9 +618,12 @@ pub async fn dfu_trigger<T: DfuTransportManager>(manager: T, target: &str) -> Re
 /// from [DFU BLE Service](https://infocenter.nordicsemi.com/topic/sdk_nrf5_v17.1.0/group__nrf__dfu__ble.html)
 /// and [Buttonless DFU Service](https://infocenter.nordicsemi.com/topic/sdk_nrf5_v17.1.0/service_dfu.html)
 #[allow(dead_code)]
-mod dfu_uuids {
+pub(crate) mod dfu_uuids {
     use uuid::Uuid;
     /// DFU Service (16 bit UUID 0xFE59)
+    ///
+    /// Nordic exposes both the bootloader DFU and the buttonless trigger under
+    /// this one 0xFE59 service, so it is the single UUID a scan filters on.
     pub const SERVICE: Uuid = Uuid::from_u128(0x0000FE59_0000_1000_8000_00805F9B34FB);
     /// Control Point Characteristic
     pub const CTRL_PT: Uuid = Uuid::from_u128(0x8EC90001_F315_4F60_9FB8_838830DAEA50);
@@ -10,12 +10,15 @@ pub trait DfuTransportManager {
 
 /// DFU transport interface
 pub trait DfuTransport {
-    /// Write without response
-    async fn write(&self, char: uuid::Uuid, bytes: &[u8]) -> Result<()>;
+    /// Write without response, splitting `bytes` into `chunk_size` packets
+    async fn write(&self, char: uuid::Uuid, bytes: &[u8], chunk_size: usize) -> Result<()>;
 
     /// Subscribe to the given characteristic
     async fn subscribe(&self, char: uuid::Uuid) -> Result<()>;
 
     /// Write with response then wait for notification response
     async fn request(&self, char: uuid::Uuid, bytes: &[u8]) -> Result<Vec<u8>>;
+
+    /// Wait for the next unsolicited notification on the given characteristic
+    async fn notify(&self, char: uuid::Uuid) -> Result<Vec<u8>>;
 }
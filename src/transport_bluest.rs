@@ -0,0 +1,121 @@
+use crate::transport::{DfuTransport, DfuTransportManager};
+
+use anyhow::{Context, Result, anyhow};
+use bluest::{Adapter, Characteristic, Device};
+use futures::stream::{Stream, StreamExt};
+use indicatif::ProgressBar;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// A single long-lived notification stream for the subscribed characteristic.
+type NotificationStream = Pin<Box<dyn Stream<Item = bluest::Result<Vec<u8>>> + Send>>;
+
+pub struct DfuTransportManagerBluest {
+    adapter: Adapter,
+}
+
+impl DfuTransportManagerBluest {
+    pub async fn new() -> anyhow::Result<Self> {
+        let adapter = Adapter::default().await.ok_or(anyhow!("No Bluetooth adapter found"))?;
+        adapter.wait_available().await?;
+        Ok(DfuTransportManagerBluest { adapter })
+    }
+
+    /// Find a device by its platform identifier or advertised name.
+    ///
+    /// CoreBluetooth never exposes MAC addresses, so macOS users target the
+    /// peripheral identifier UUID reported by [`Device::id`] instead.
+    async fn find_device(&self, target: &str, pb: &ProgressBar) -> Result<Device> {
+        let mut scan = self.adapter.scan(&[]).await?;
+        while let Some(discovered) = scan.next().await {
+            let device = discovered.device;
+            let id = device.id().to_string();
+            let name = discovered.adv_data.local_name.clone();
+            pb.set_message(format!(
+                "id: {}, name: {}",
+                id,
+                name.as_deref().unwrap_or("None"),
+            ));
+            if id == target || name.as_deref() == Some(target) {
+                return Ok(device);
+            }
+        }
+        Err(anyhow!("Scanning stopped unexpectedly"))
+    }
+}
+
+impl DfuTransportManager for DfuTransportManagerBluest {
+    type Transport = DfuTransportBluest;
+
+    async fn connect(&self, target: &str) -> anyhow::Result<Self::Transport> {
+        let pb = ProgressBar::new_spinner();
+        pb.enable_steady_tick(Duration::from_millis(64));
+        pb.println(format!("Searching for `{}`...", target));
+
+        let device = self.find_device(target, &pb).await?;
+        self.adapter.connect_device(&device).await.context("Failed to establish a connection")?;
+
+        let mut characteristics = Vec::new();
+        for service in device.discover_services().await.context("Service discovery failed")? {
+            characteristics.extend(service.discover_characteristics().await?);
+        }
+
+        pb.finish();
+        Ok(DfuTransportBluest {
+            characteristics,
+            notifications: Mutex::new(None),
+        })
+    }
+}
+
+pub struct DfuTransportBluest {
+    characteristics: Vec<Characteristic>,
+    notifications: Mutex<Option<NotificationStream>>,
+}
+
+impl DfuTransportBluest {
+    fn characteristic(&self, uuid: uuid::Uuid) -> Result<&Characteristic> {
+        self.characteristics
+            .iter()
+            .find(|char| char.uuid() == uuid)
+            .ok_or(anyhow!("characteristic not found"))
+    }
+}
+
+impl DfuTransport for DfuTransportBluest {
+    async fn subscribe(&self, char: uuid::Uuid) -> Result<()> {
+        let char = self.characteristic(char)?;
+        let stream = char.notify().await?;
+        *self.notifications.lock().await = Some(Box::pin(stream));
+        Ok(())
+    }
+
+    async fn write(&self, char: uuid::Uuid, bytes: &[u8], chunk_size: usize) -> Result<()> {
+        let char = self.characteristic(char)?;
+        for chunk in bytes.chunks(chunk_size) {
+            char.write_without_response(chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn request(&self, char: uuid::Uuid, bytes: &[u8]) -> Result<Vec<u8>> {
+        let char = self.characteristic(char)?;
+        let mut guard = self.notifications.lock().await;
+        let stream = guard.as_mut().context("characteristic not subscribed")?;
+        char.write(bytes).await?;
+        match stream.next().await {
+            Some(ntf) => Ok(ntf?),
+            None => Err(anyhow!("Notifications stopped unexpectedly")),
+        }
+    }
+
+    async fn notify(&self, _char: uuid::Uuid) -> Result<Vec<u8>> {
+        let mut guard = self.notifications.lock().await;
+        let stream = guard.as_mut().context("characteristic not subscribed")?;
+        match stream.next().await {
+            Some(ntf) => Ok(ntf?),
+            None => Err(anyhow!("Notifications stopped unexpectedly")),
+        }
+    }
+}
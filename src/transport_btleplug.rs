@@ -1,14 +1,21 @@
+use crate::protocol::dfu_uuids;
 use crate::transport::{DfuTransport, DfuTransportManager};
 
 use anyhow::{Context, Result, anyhow};
 use btleplug::api::{
-    BDAddr, Central, CentralEvent, Characteristic, Manager as _, Peripheral as _, PeripheralProperties, ScanFilter, WriteType,
+    BDAddr, Central, CentralEvent, Characteristic, Manager as _, Peripheral as _, PeripheralProperties, ScanFilter,
+    ValueNotification, WriteType,
 };
 use btleplug::platform::Adapter;
 use btleplug::platform::Peripheral;
-use futures::stream::StreamExt;
+use futures::stream::{Stream, StreamExt};
 use indicatif::ProgressBar;
+use std::pin::Pin;
 use std::str::FromStr;
+use tokio::sync::Mutex;
+
+/// A single long-lived notification stream shared across control-point requests.
+type NotificationStream = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
 
 pub struct DfuTransportManagerBtleplug {
     adapter: Adapter,
@@ -52,6 +59,37 @@ impl DfuTransportManagerBtleplug {
         format!("rssi: {}, address: {}, name: {}", rssi, addr, name)
     }
 
+    /// Scan for DFU-capable peripherals and print what advertises nearby.
+    ///
+    /// Unlike [`find_peripheral`](Self::find_peripheral), which returns the
+    /// first exact match, this collects every peripheral advertising either the
+    /// DFU service or the Buttonless DFU trigger service for `duration` and lists
+    /// them, so a bootloader-mode device can be found without knowing its name.
+    pub async fn scan(&self, duration: std::time::Duration) -> Result<()> {
+        let filter = ScanFilter {
+            services: vec![dfu_uuids::SERVICE],
+        };
+        self.adapter.start_scan(filter).await?;
+        tokio::time::sleep(duration).await;
+        self.adapter.stop_scan().await?;
+
+        let mut found = 0;
+        for peripheral in self.adapter.peripherals().await? {
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+            if !properties.services.contains(&dfu_uuids::SERVICE) {
+                continue;
+            }
+            found += 1;
+            println!("{}", Self::format_peripheral_properties(&properties));
+        }
+        if found == 0 {
+            eprintln!("No DFU-capable peripherals found");
+        }
+        Ok(())
+    }
+
     #[cfg(target_os = "macos")]
     async fn find_peripheral_by_address(&self, _addr: &BDAddr, _pb: &ProgressBar) -> Result<Peripheral> {
         Err(anyhow!("BLE MAC addresses are not supported on macOS"))
@@ -95,13 +133,18 @@ impl DfuTransportManager for DfuTransportManagerBtleplug {
         peripheral.connect().await.context("Failed to establish a connection")?;
         peripheral.discover_services().await.context("Service discovery failed")?;
 
+        // Open the notification stream once, up front, so notifications that
+        // arrive between requests are buffered rather than dropped.
+        let notifications = Mutex::new(peripheral.notifications().await?);
+
         pb.finish();
-        Ok(DfuTransportBtleplug { peripheral })
+        Ok(DfuTransportBtleplug { peripheral, notifications })
     }
 }
 
 pub struct DfuTransportBtleplug {
     peripheral: Peripheral,
+    notifications: Mutex<NotificationStream>,
 }
 
 impl DfuTransportBtleplug {
@@ -122,20 +165,17 @@ impl DfuTransport for DfuTransportBtleplug {
         Ok(())
     }
 
-    async fn write(&self, char: uuid::Uuid, bytes: &[u8]) -> Result<()> {
+    async fn write(&self, char: uuid::Uuid, bytes: &[u8], chunk_size: usize) -> Result<()> {
         let char = self.characteristic(char)?;
-        // TODO: fix this once btleplug supports MTU discovery
-        // default nRF DFU MTU is 244
-        const MTU: usize = 244;
-        for chunk in bytes.chunks(MTU) {
+        for chunk in bytes.chunks(chunk_size) {
             self.peripheral.write(&char, chunk, WriteType::WithoutResponse).await?;
         }
         Ok(())
     }
 
     async fn request(&self, char: uuid::Uuid, bytes: &[u8]) -> Result<Vec<u8>> {
-        let mut notifications = self.peripheral.notifications().await?;
         let char = self.characteristic(char)?;
+        let mut notifications = self.notifications.lock().await;
         self.peripheral.write(&char, bytes, WriteType::WithResponse).await?;
         while let Some(ntf) = notifications.next().await {
             if ntf.uuid == char.uuid {
@@ -144,4 +184,14 @@ impl DfuTransport for DfuTransportBtleplug {
         }
         Err(anyhow!("Notifications stopped unexpectedly"))
     }
+
+    async fn notify(&self, char: uuid::Uuid) -> Result<Vec<u8>> {
+        let mut notifications = self.notifications.lock().await;
+        while let Some(ntf) = notifications.next().await {
+            if ntf.uuid == char {
+                return Ok(ntf.value);
+            }
+        }
+        Err(anyhow!("Notifications stopped unexpectedly"))
+    }
 }